@@ -2,6 +2,14 @@ use std::time::{Instant, Duration};
 use std::mem::MaybeUninit;
 use std::rc::Rc;
 use std::marker::PhantomData;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ptr;
+use std::collections::VecDeque;
+use std::cell::UnsafeCell;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub enum Memoized<'a, I, T> {
     UnInitialized(Box<dyn 'a + FnMut(I) -> T>),
@@ -66,6 +74,436 @@ pub fn memoize_with_expiration<'a, I, T, F: 'a + FnMut(I) -> T>(f: F, duration:
     MemoizedWithExpiration::new(f, duration)
 }
 
+pub struct KeyedMemoized<'a, I: Hash + Eq + Clone, T> {
+    f: Box<'a + FnMut(I) -> T>,
+    cache: HashMap<I, T>,
+}
+
+impl<'a, I: Hash + Eq + Clone, T> KeyedMemoized<'a, I, T> {
+    pub fn new<F: 'a + FnMut(I) -> T>(f: F) -> Self {
+        Self {
+            f: Box::new(f),
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, input: I) -> &T {
+        if !self.cache.contains_key(&input) {
+            let t = (self.f)(input.clone());
+            self.cache.insert(input.clone(), t);
+        }
+        self.cache.get(&input).unwrap()
+    }
+
+    pub fn invalidate(&mut self, input: &I) -> Option<T> {
+        self.cache.remove(input)
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    pub fn retain<F: FnMut(&I, &T) -> bool>(&mut self, mut f: F) {
+        self.cache.retain(|k, v| f(k, v));
+    }
+}
+
+pub fn memoize_keyed<'a, I: Hash + Eq + Clone, T, F: 'a + FnMut(I) -> T>(f: F) -> KeyedMemoized<'a, I, T> {
+    KeyedMemoized::new(f)
+}
+
+struct LruNode<I, T> {
+    key: MaybeUninit<I>,
+    value: MaybeUninit<T>,
+    prev: *mut LruNode<I, T>,
+    next: *mut LruNode<I, T>,
+}
+
+impl<I, T> LruNode<I, T> {
+    fn sentinel() -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            key: MaybeUninit::uninit(),
+            value: MaybeUninit::uninit(),
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        }))
+    }
+}
+
+pub struct LruMemoized<'a, I: Hash + Eq + Clone, T> {
+    f: Box<'a + FnMut(I) -> T>,
+    capacity: usize,
+    map: HashMap<I, *mut LruNode<I, T>>,
+    // `head`/`tail` are sentinels; the list runs most- to least-recently-used
+    // from `head.next` to `tail.prev`.
+    head: *mut LruNode<I, T>,
+    tail: *mut LruNode<I, T>,
+}
+
+impl<'a, I: Hash + Eq + Clone, T> LruMemoized<'a, I, T> {
+    pub fn with_capacity<F: 'a + FnMut(I) -> T>(f: F, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        let head = LruNode::sentinel();
+        let tail = LruNode::sentinel();
+        unsafe {
+            (*head).next = tail;
+            (*tail).prev = head;
+        }
+        Self {
+            f: Box::new(f),
+            capacity,
+            map: HashMap::new(),
+            head,
+            tail,
+        }
+    }
+
+    pub fn get(&mut self, input: I) -> &T {
+        if let Some(&node) = self.map.get(&input) {
+            unsafe {
+                self.detach(node);
+                self.attach_front(node);
+                return &*(*node).value.as_ptr();
+            }
+        }
+
+        let value = (self.f)(input.clone());
+        let node = Box::into_raw(Box::new(LruNode {
+            key: MaybeUninit::new(input.clone()),
+            value: MaybeUninit::new(value),
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        }));
+        self.map.insert(input, node);
+        unsafe {
+            self.attach_front(node);
+        }
+
+        if self.map.len() > self.capacity {
+            self.evict_lru();
+        }
+
+        unsafe { &*(*node).value.as_ptr() }
+    }
+
+    pub fn peek(&self, input: &I) -> Option<&T> {
+        self.map.get(input).map(|&node| unsafe { &*(*node).value.as_ptr() })
+    }
+
+    pub fn invalidate(&mut self, input: &I) -> Option<T> {
+        let node = self.map.remove(input)?;
+        unsafe {
+            self.detach(node);
+            let boxed = Box::from_raw(node);
+            let LruNode { key, value, .. } = *boxed;
+            drop(key.assume_init());
+            Some(value.assume_init())
+        }
+    }
+
+    pub fn clear(&mut self) {
+        unsafe {
+            let mut node = (*self.head).next;
+            while node != self.tail {
+                let next = (*node).next;
+                let boxed = Box::from_raw(node);
+                let LruNode { key, value, .. } = *boxed;
+                drop(key.assume_init());
+                drop(value.assume_init());
+                node = next;
+            }
+            (*self.head).next = self.tail;
+            (*self.tail).prev = self.head;
+        }
+        self.map.clear();
+    }
+
+    pub fn retain<F: FnMut(&I, &T) -> bool>(&mut self, mut f: F) {
+        let to_remove: Vec<I> = self
+            .map
+            .iter()
+            .filter(|&(k, &node)| !unsafe { f(k, &*(*node).value.as_ptr()) })
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in to_remove {
+            self.invalidate(&key);
+        }
+    }
+
+    unsafe fn detach(&mut self, node: *mut LruNode<I, T>) {
+        let prev = (*node).prev;
+        let next = (*node).next;
+        (*prev).next = next;
+        (*next).prev = prev;
+    }
+
+    unsafe fn attach_front(&mut self, node: *mut LruNode<I, T>) {
+        let first = (*self.head).next;
+        (*node).prev = self.head;
+        (*node).next = first;
+        (*self.head).next = node;
+        (*first).prev = node;
+    }
+
+    fn evict_lru(&mut self) {
+        unsafe {
+            let lru = (*self.tail).prev;
+            self.detach(lru);
+            let boxed = Box::from_raw(lru);
+            let LruNode { key, value, .. } = *boxed;
+            self.map.remove(&key.assume_init());
+            drop(value.assume_init());
+        }
+    }
+}
+
+impl<'a, I: Hash + Eq + Clone, T> Drop for LruMemoized<'a, I, T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut node = (*self.head).next;
+            while node != self.tail {
+                let next = (*node).next;
+                let boxed = Box::from_raw(node);
+                let LruNode { key, value, .. } = *boxed;
+                drop(key.assume_init());
+                drop(value.assume_init());
+                node = next;
+            }
+            drop(Box::from_raw(self.head));
+            drop(Box::from_raw(self.tail));
+        }
+    }
+}
+
+pub fn memoize_lru<'a, I: Hash + Eq + Clone, T, F: 'a + FnMut(I) -> T>(f: F, capacity: usize) -> LruMemoized<'a, I, T> {
+    LruMemoized::with_capacity(f, capacity)
+}
+
+pub struct KeyedMemoizedWithExpiration<'a, I: Hash + Eq + Clone, T> {
+    f: Box<'a + FnMut(I) -> T>,
+    ttl: Duration,
+    cache: HashMap<I, (T, Instant)>,
+    // Insertion order, oldest first, so `remove_expired` only has to walk
+    // the stale prefix instead of scanning the whole map.
+    order: VecDeque<(I, Instant)>,
+}
+
+impl<'a, I: Hash + Eq + Clone, T> KeyedMemoizedWithExpiration<'a, I, T> {
+    pub fn new<F: 'a + FnMut(I) -> T>(f: F, ttl: Duration) -> Self {
+        Self {
+            f: Box::new(f),
+            ttl,
+            cache: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&mut self, input: I) -> &T {
+        self.remove_expired();
+
+        if !self.cache.contains_key(&input) {
+            let value = (self.f)(input.clone());
+            let now = Instant::now();
+            self.cache.insert(input.clone(), (value, now));
+            self.order.push_back((input.clone(), now));
+        }
+
+        &self.cache.get(&input).unwrap().0
+    }
+
+    pub fn invalidate(&mut self, input: &I) -> Option<T> {
+        let removed = self.cache.remove(input).map(|(value, _)| value);
+        if removed.is_some() {
+            self.order.retain(|(k, _)| k != input);
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.order.clear();
+    }
+
+    pub fn retain<F: FnMut(&I, &T) -> bool>(&mut self, mut f: F) {
+        self.remove_expired();
+        self.cache.retain(|k, (v, _)| f(k, v));
+        let cache = &self.cache;
+        self.order.retain(|(k, _)| cache.contains_key(k));
+    }
+
+    fn remove_expired(&mut self) {
+        while let Some((key, inserted)) = self.order.front() {
+            match self.cache.get(key) {
+                // Still the live entry for this key: as soon as we hit one
+                // that hasn't expired, every entry behind it is younger.
+                Some((_, current)) if current == inserted => {
+                    if inserted.elapsed() > self.ttl {
+                        let key = key.clone();
+                        self.order.pop_front();
+                        self.cache.remove(&key);
+                    } else {
+                        break;
+                    }
+                }
+                // A queue entry whose key is no longer in `cache` or was
+                // re-inserted later: drop it (`invalidate` and `retain`
+                // already purge their own entries from `order` directly).
+                _ => {
+                    self.order.pop_front();
+                }
+            }
+        }
+    }
+}
+
+pub fn memoize_keyed_with_expiration<'a, I: Hash + Eq + Clone, T, F: 'a + FnMut(I) -> T>(f: F, ttl: Duration) -> KeyedMemoizedWithExpiration<'a, I, T> {
+    KeyedMemoizedWithExpiration::new(f, ttl)
+}
+
+pub struct SyncMemoized<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    initialized: AtomicBool,
+    lock: Mutex<()>,
+}
+
+unsafe impl<T: Send + Sync> Sync for SyncMemoized<T> {}
+
+impl<T> SyncMemoized<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            initialized: AtomicBool::new(false),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.initialized.load(Ordering::Acquire) {
+            Some(unsafe { &*(*self.value.get()).as_ptr() })
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let _guard = self.lock.lock().unwrap_or_else(|p| p.into_inner());
+        if self.initialized.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        unsafe {
+            (*self.value.get()).write(value);
+        }
+        self.initialized.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if !self.initialized.load(Ordering::Acquire) {
+            let _guard = self.lock.lock().unwrap_or_else(|p| p.into_inner());
+            // Check again: another thread may have initialized while we
+            // were waiting for the lock. If `f` panics, the guard is
+            // dropped (poisoning the lock, which we ignore above) and
+            // `initialized` is never set, so a later call can retry.
+            if !self.initialized.load(Ordering::Acquire) {
+                let value = f();
+                unsafe {
+                    (*self.value.get()).write(value);
+                }
+                self.initialized.store(true, Ordering::Release);
+            }
+        }
+        unsafe { &*(*self.value.get()).as_ptr() }
+    }
+}
+
+impl<T> Default for SyncMemoized<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for SyncMemoized<T> {
+    fn drop(&mut self) {
+        if *self.initialized.get_mut() {
+            unsafe {
+                ptr::drop_in_place((*self.value.get()).as_mut_ptr());
+            }
+        }
+    }
+}
+
+pub struct SyncKeyedMemoized<I, T> {
+    map: Mutex<HashMap<I, Arc<T>>>,
+}
+
+impl<I: Hash + Eq, T> SyncKeyedMemoized<I, T> {
+    pub fn new() -> Self {
+        Self {
+            map: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &I) -> Option<Arc<T>> {
+        self.lock().get(key).cloned()
+    }
+
+    pub fn get_or_init<F: FnOnce() -> T>(&self, key: I, f: F) -> Arc<T> {
+        let mut map = self.lock();
+        if let Some(value) = map.get(&key) {
+            return Arc::clone(value);
+        }
+        let value = Arc::new(f());
+        map.insert(key, Arc::clone(&value));
+        value
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<I, Arc<T>>> {
+        self.map.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl<I: Hash + Eq, T> Default for SyncKeyedMemoized<I, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Lazy<T, F = fn() -> T> {
+    cell: SyncMemoized<T>,
+    init: Mutex<Option<F>>,
+}
+
+impl<T, F> Lazy<T, F> {
+    pub const fn new(f: F) -> Self {
+        Self {
+            cell: SyncMemoized::new(),
+            init: Mutex::new(Some(f)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| {
+            let f = this
+                .init
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .take()
+                .expect("Lazy instance's initializer panicked on an earlier call");
+            f()
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,4 +572,182 @@ mod tests {
         sleep(Duration::from_secs(1));
         assert_eq!(m.m.get(Box::new(m.d)), Rc::new(11));
     }
+
+    #[test]
+    fn test_memoize_keyed() {
+        let mut called = 0;
+        let mut m = memoize_keyed(move |i: i32| {
+            called += 1;
+            (i, called)
+        });
+        assert_eq!(m.get(1), &(1, 1));
+        assert_eq!(m.get(1), &(1, 1));
+        assert_eq!(m.get(10), &(10, 2));
+        assert_eq!(m.get(1), &(1, 1));
+    }
+
+    #[test]
+    fn test_memoize_keyed_invalidate_clear_retain() {
+        let mut called = 0;
+        let mut m = memoize_keyed(move |i: i32| {
+            called += 1;
+            (i, called)
+        });
+        m.get(1);
+        m.get(2);
+        m.get(3);
+        assert_eq!(m.invalidate(&2), Some((2, 2)));
+        assert_eq!(m.invalidate(&2), None);
+        assert_eq!(m.get(2), &(2, 4));
+        m.retain(|k, _| *k != 1);
+        assert_eq!(m.get(1), &(1, 5));
+        m.clear();
+        assert_eq!(m.get(2), &(2, 6));
+    }
+
+    #[test]
+    fn test_memoize_lru() {
+        let mut called = 0;
+        let mut m = memoize_lru(move |i: i32| {
+            called += 1;
+            (i, called)
+        }, 2);
+        assert_eq!(m.get(1), &(1, 1));
+        assert_eq!(m.get(2), &(2, 2));
+        assert_eq!(m.get(1), &(1, 1));
+        // 2 is now the least-recently-used entry and gets evicted.
+        assert_eq!(m.get(3), &(3, 3));
+        assert_eq!(m.peek(&2), None);
+        assert_eq!(m.get(2), &(2, 4));
+    }
+
+    #[test]
+    fn test_memoize_lru_invalidate_clear_retain() {
+        let mut called = 0;
+        let mut m = memoize_lru(move |i: i32| {
+            called += 1;
+            (i, called)
+        }, 3);
+        m.get(1);
+        m.get(2);
+        m.get(3);
+        assert_eq!(m.invalidate(&2), Some((2, 2)));
+        assert_eq!(m.invalidate(&2), None);
+        assert_eq!(m.peek(&2), None);
+        m.retain(|k, _| *k != 1);
+        assert_eq!(m.peek(&1), None);
+        assert_eq!(m.peek(&3), Some(&(3, 3)));
+        m.clear();
+        assert_eq!(m.peek(&3), None);
+        assert_eq!(m.get(3), &(3, 4));
+    }
+
+    #[test]
+    fn test_memoize_keyed_with_expiration() {
+        let mut called = 0;
+        let mut m = memoize_keyed_with_expiration(move |i: i32| {
+            called += 1;
+            (i, called)
+        }, Duration::from_secs(1));
+        assert_eq!(m.get(1), &(1, 1));
+        assert_eq!(m.get(2), &(2, 2));
+        assert_eq!(m.get(1), &(1, 1));
+        sleep(Duration::from_secs(1));
+        assert_eq!(m.get(1), &(1, 3));
+        assert_eq!(m.get(2), &(2, 4));
+    }
+
+    #[test]
+    fn test_memoize_keyed_with_expiration_invalidate_clear_retain() {
+        let mut called = 0;
+        let mut m = memoize_keyed_with_expiration(move |i: i32| {
+            called += 1;
+            (i, called)
+        }, Duration::from_secs(60));
+        m.get(1);
+        m.get(2);
+        m.get(3);
+        assert_eq!(m.invalidate(&2), Some((2, 2)));
+        assert_eq!(m.invalidate(&2), None);
+        m.retain(|k, _| *k != 1);
+        assert_eq!(m.get(1), &(1, 4));
+        assert_eq!(m.get(3), &(3, 3));
+        m.clear();
+        assert_eq!(m.get(3), &(3, 5));
+    }
+
+    #[test]
+    fn test_memoize_keyed_with_expiration_order_does_not_leak() {
+        let mut m = memoize_keyed_with_expiration(|i: i32| i, Duration::from_secs(60));
+        m.get(-1);
+        for i in 0..5000 {
+            m.get(i);
+            m.retain(|&k, _| k == -1);
+        }
+        assert_eq!(m.cache.len(), 1);
+        assert_eq!(m.order.len(), 1);
+    }
+
+    #[test]
+    fn test_sync_memoized() {
+        let m = SyncMemoized::new();
+        assert_eq!(m.get(), None);
+        assert_eq!(m.get_or_init(|| 1), &1);
+        assert_eq!(m.get_or_init(|| 2), &1);
+        assert_eq!(m.get(), Some(&1));
+        assert_eq!(m.set(3), Err(3));
+    }
+
+    #[test]
+    fn test_sync_memoized_across_threads() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let m = Arc::new(SyncMemoized::new());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let calls = Arc::clone(&calls);
+                let m = Arc::clone(&m);
+                thread::spawn(move || {
+                    *m.get_or_init(|| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        42
+                    })
+                })
+            })
+            .collect();
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_sync_keyed_memoized() {
+        let m = SyncKeyedMemoized::new();
+        assert_eq!(m.get(&1), None);
+        assert_eq!(*m.get_or_init(1, || 10), 10);
+        assert_eq!(*m.get_or_init(1, || 20), 10);
+        assert_eq!(*m.get_or_init(2, || 20), 20);
+    }
+
+    #[test]
+    fn test_lazy() {
+        let mut called = 0;
+        let l = Lazy::new(|| {
+            called += 1;
+            called
+        });
+        assert_eq!(*l, 1);
+        assert_eq!(*l, 1);
+    }
+
+    static GLOBAL: Lazy<i32> = Lazy::new(|| 7);
+
+    #[test]
+    fn test_lazy_static() {
+        assert_eq!(*GLOBAL, 7);
+    }
 }